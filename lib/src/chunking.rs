@@ -0,0 +1,344 @@
+//! Chunking: splits a byte stream into content-defined chunks
+//!
+//! Every algorithm here implements `Chunking`, which is fed successive
+//! buffers of input and reports where chunk boundaries ("edges") fall, so
+//! the caller can cut the stream into independently addressable chunks
+//! for deduplication.
+
+/// 256-entry table of pseudo-random 64 bit values used by the rolling
+/// "gear" hash: `fp = (fp << 1).wrapping_add(GEAR[byte as usize])`.
+/// A single table lookup spreads each byte's influence across all 64
+/// bits with minimal bias, which is what several of the algorithms below
+/// rely on for an even distribution of cut points.
+const GEAR: [u64; 256] = [
+    0x950e87d7f5606615, 0x2c61275c9e6b6cf8, 0x1f00bca0042db923, 0x6dbca290a9eab706,
+    0x4c10a4fe30cffdda, 0xf26fff4cc4fd394d, 0x6814a2bc786a6d2d, 0xa26b351e6c8042c5,
+    0x54760e7fbc051c6c, 0xd4c08880a5a4666d, 0x29610ae0eed8f1e7, 0xc34bd8e2fe5213e5,
+    0x6c50afb6e9fb123d, 0x6f28d015a2aa0b9d, 0x4e385994ebac94af, 0x194f9545adba52ce,
+    0xc675ce05588f882f, 0x57de8c051d4b7ef2, 0xd998efd82733e933, 0x6df216c33f8f3201,
+    0x11dc6f3fcb57d5d8, 0x8860a84722025e05, 0x33176469aa6ef630, 0x607507ebc5b864d7,
+    0x7a2f11088d29b146, 0xda10faaa6fc24b83, 0x2de288f12fcb9940, 0xb98937dfef041066,
+    0xdd4b712ed355871e, 0xc5b790314a2e3224, 0x07fdc889fa017ed7, 0x81eeadd71198bf15,
+    0x3a46305c425a7de1, 0xaaabc8d366e0440d, 0x3371364fc51d1a5e, 0x4763dd191ac44b70,
+    0x016590c55646e6d0, 0x0b7a6e1d81e4b9e7, 0xe5a2a8bef16e981a, 0x1167fba4a2927979,
+    0x3d01ac0f1b534b87, 0xd27a5f0f5532c867, 0xee26cbc0358b24d3, 0x9bdb39b2ca3c6a00,
+    0x8de06fbe1a741555, 0xd6257b492186c8b5, 0xdee7539c539445f3, 0x4307513f1ec1b0b1,
+    0x1d790bcaeffd4d2d, 0xde18f50a43cf423a, 0xd36c78ab3537a844, 0x64b5e3f81a293b3b,
+    0xe8eef3d67646f8a9, 0xa88d379db047719d, 0xf177d49f03ddc3bf, 0xa745fdd552965bca,
+    0xd0b6a46a7048daca, 0xfce79398852e0400, 0x760c9b756320dbe3, 0x4e52b41980271e94,
+    0x293f65848aa18f43, 0x520e015e444ed0f2, 0x793ff51bb0baf029, 0x7ad955568f86a26a,
+    0x1c720603ec8602d9, 0xd08e7565d487d342, 0x310288290b43dbfb, 0xd50ca99e8e59ea07,
+    0x6c24e82c6dbbac73, 0xb7a13dce8e4595df, 0xe91b8ec1f011e633, 0x9293bf4aed9a76b9,
+    0x75c33f8fcb8031fe, 0x1e7c31d385989296, 0x5574e314ddfc20fe, 0xd17dad339930e76e,
+    0xacfbba2a3f8666ee, 0xa4e307830deef007, 0x8fcd110ce94f47b0, 0xe1660a4195d74835,
+    0xd6d91d39227d512d, 0x2abb018969cbe6eb, 0x09cea2a86a921843, 0x3fe9e76493a8b5d8,
+    0x602f8e87d16bc8be, 0xe376bd78d7304cb6, 0x748781c961ef7dfc, 0xff5e243c496a590b,
+    0x089934a93d71d058, 0x3deadc7d1d2e1a2e, 0xe443e6031233f1e0, 0x5ab59d10b4a20569,
+    0x658141e73ede6f12, 0xf5d46d8127762b7b, 0xad1dd1408b87cfcb, 0xf9afa64760083c7d,
+    0xb7a68aa8611b9b59, 0xd828056ea86fc09c, 0x1c0ae9a87893032b, 0x34c8a05ca34be96a,
+    0xc966aed65a10eeaf, 0x6b7e21f0921082df, 0x6e5d9a3007c331a3, 0x3a0806a754f57983,
+    0x0a07a198f7767fd6, 0xf0723a8383f43dc4, 0xfb65e62582414d3f, 0x504516f2106025b5,
+    0xa0d72f15feb859eb, 0x115600523ea6fb4d, 0x1be3ae0c3b97b6c9, 0x5fe2b11364b97756,
+    0x5a8a944097dea5e8, 0xc330642bbf1317f8, 0xf0b02956ff594f79, 0xa4002d902b1b1e58,
+    0xba351d1d2912ab9f, 0x56761e8879073c59, 0x3912a0fca373e01b, 0xec004af1d0efd4ff,
+    0x8919551203d33d87, 0x64f85da91a44dfa0, 0x21d287d8efb4cad1, 0x1732b75d08d75496,
+    0x27623245c6251a5c, 0x987abb69ec5093da, 0xea45cdaf628e21c8, 0x0272834f4d8a9084,
+    0xab699ad2c231185b, 0x6ff327f4119ee914, 0x6b06b34098ca4c3f, 0x725461191d5d7302,
+    0x511173b251af8015, 0xebbfbb2bc3846ece, 0xed8b79ed1d74a080, 0x9736b29f0b03d0e1,
+    0xceaf0df42de3540c, 0x576c473aecbeb26f, 0x6782e42f80a0f27d, 0xf39f015e2cafb91c,
+    0x293c27e425e74da2, 0x1a18b9b1c2c8b502, 0x731535ecb7b2a53b, 0x4f7d9b08c0f76e59,
+    0x3e115e3e75118be1, 0x689db40cdd801db4, 0x399246294d8fc042, 0xc018ee73ff8f5cff,
+    0xa364f1b057f4865e, 0xbd5993b1f9f2dce0, 0x1fb37062a68f65c1, 0x2a5f2d8aca707a92,
+    0x3ff1295c1d296c14, 0x4ea7feaa1455fcad, 0xb484b8d3f354db28, 0xdef5e3507a2ee034,
+    0x1a46b9e3a2663f03, 0x5665aca3177d70d6, 0x36a208e01b1b4ee3, 0x00822ed4e33a0336,
+    0x9d3bd30e22749e54, 0x703666d165265fe5, 0xebe4418c6286ef71, 0xe07f915527fcb0f2,
+    0xcfedc87950868c9c, 0x95825097784ecbbb, 0x106572c92038d12e, 0x79b713272176822e,
+    0x810287a90cffae31, 0x7c8f5a44b03c1008, 0x113167635255aa79, 0x9f0600356aab79e5,
+    0x559ccfb8c80ce420, 0x33fc57dd263695f9, 0xc2299345df0b305d, 0x3519cb88dac97abb,
+    0xed1137eb3e5e1046, 0x22b6ce988e5e8733, 0xe3bd76bf57cec991, 0x402117a53e2681d1,
+    0xeee4852d330c2394, 0x854773512f3334bf, 0xcfe680854c95ea72, 0xe3aab3ddc209f79d,
+    0xa2842cb2fb44c6a2, 0x32442b01a0f4dd5a, 0xe5fbc6d02bd667d6, 0x343c5382621d123a,
+    0x6cb5b7d2782a1890, 0xef04a4a598411feb, 0x31afaa01fdc2dbd7, 0x5762032f27aa949b,
+    0x332508b2d1c97795, 0xb93ad7dfcba7ddcd, 0x4930986a215c9b8b, 0x3caf648a3fe36a17,
+    0x4e1309a0fc447a7f, 0x019d6ac5fe7f773e, 0x637118bb0b0e773c, 0xba17e7bd0a7a8b0c,
+    0x20b9122fca694c79, 0xb0773e1b8ea50117, 0xa544b6d2cf823377, 0x3e2e21041529057c,
+    0x01d6aedaa22e88e8, 0x673bb9153bc7eead, 0xf332dec5058c062b, 0x802df2eef9537531,
+    0x26dd7c451562a836, 0x0c72e5f1f03cde37, 0xeae27c2bcf28335a, 0x9482faca03ac665d,
+    0x6774a90031d2ba09, 0xe6b37c203fbd6d30, 0xc958935b157304b1, 0x9ef80467a8e636c6,
+    0xa7d73426f0aee715, 0x4ac05557bdca343f, 0x65c2195389de9f30, 0x7b4afcc0a8108c27,
+    0x938f35b2dc04bbfc, 0x642e484600cdfa67, 0x890c62927989d7e6, 0x11d0bc174b47a18b,
+    0xd0ae2b468f227e2f, 0xb9f409d40d3832c1, 0xa37579c44c86abf9, 0xcc69f35beecff786,
+    0x3cd64d14ac521437, 0xb860c5a45b4be237, 0x3d1791cf2b9550bc, 0x4c5b4726a89a476e,
+    0x12e2992b24380fb6, 0x0fb88164ccc14927, 0x9dca0bdcdd3a68c5, 0xeb0e37f4d6290f03,
+    0x0e8936d8133fee34, 0x2e778e78671eaa35, 0x616eb2a9fb09b28d, 0xaac0c22e5d235cab,
+    0xad4cf62c94a4f317, 0xcf3b5ee99ca944bb, 0xc1f007cd2413872a, 0x18fde7a7091e9247,
+    0xe8ed59599a0e9c30, 0xb036bade9e716b3d, 0x92852160c8b912b1, 0x59ad98498ff5b11b,
+    0xd41339c948a6e7cb, 0x3c79a0009f140b4e, 0x34186cdd3c3c5140, 0x919b6a673343fd70,
+    0xbab5120ef942a0f6, 0x3c8016d006c1ec71, 0x28e208906796f59f, 0xfbd9efbb76c9773a,
+];
+
+/// A chunking algorithm: finds chunk boundaries in a stream of bytes.
+///
+/// Implementations are fed successive buffers via `find_chunk_edge` and
+/// keep whatever rolling state they need between calls. A returned
+/// `Some(offset)` is the position, relative to the start of `buf`, of a
+/// chunk edge; the caller is expected to start a new chunk there and
+/// feed the remainder of `buf` back in before resuming.
+pub trait Chunking: Send {
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<usize>;
+}
+
+/// `Bup` chunker, cutting when the low `chunk_bits` bits of a running sum
+/// of the input bytes are all zero. The sum starts from zero on every
+/// `find_chunk_edge` call and covers every byte since; it is not a
+/// windowed rolling checksum, and no state is kept between calls.
+pub struct Bup {
+    chunk_bits: u32,
+}
+
+impl Bup {
+    pub fn new(chunk_bits: u32) -> Self {
+        Bup { chunk_bits }
+    }
+}
+
+impl Chunking for Bup {
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<usize> {
+        let mask = (1u64 << self.chunk_bits) - 1;
+        let mut fp = 0u64;
+        for (i, &b) in buf.iter().enumerate() {
+            fp = fp.wrapping_add(u64::from(b));
+            if fp & mask == 0 {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+/// `Gear` rolling hash, cutting when the low `chunk_bits` bits of the
+/// hash are all zero.
+pub struct Gear {
+    chunk_bits: u32,
+    fp: u64,
+}
+
+impl Gear {
+    pub fn new(chunk_bits: u32) -> Self {
+        Gear { chunk_bits, fp: 0 }
+    }
+}
+
+impl Chunking for Gear {
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<usize> {
+        let mask = (1u64 << self.chunk_bits) - 1;
+        for (i, &b) in buf.iter().enumerate() {
+            self.fp = (self.fp << 1).wrapping_add(GEAR[b as usize]);
+            if self.fp & mask == 0 {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+/// FastCDC: a Gear-hash chunker with *normalized chunking*.
+///
+/// Normalized chunking uses two masks of different strictness: a
+/// "strict" mask with more set bits while we're still below the target
+/// chunk size, and a "lenient" mask with fewer set bits once we're past
+/// it. Cutting against the harder mask early and the easier mask late
+/// makes chunk sizes cluster tightly around `normal_size`, which gives
+/// FastCDC its much lower size variance compared to plain rollsum/Gear.
+pub struct FastCdc {
+    min_size: usize,
+    normal_size: usize,
+    max_size: usize,
+    mask_s: u64,
+    mask_l: u64,
+    pos: usize,
+    fp: u64,
+}
+
+impl FastCdc {
+    pub fn new(chunk_bits: u32) -> Self {
+        let normal_size = 1usize << chunk_bits;
+        FastCdc {
+            min_size: normal_size / 4,
+            normal_size,
+            max_size: normal_size * 8,
+            mask_s: (1u64 << (chunk_bits + 2)) - 1,
+            mask_l: (1u64 << (chunk_bits.saturating_sub(2))) - 1,
+            pos: 0,
+            fp: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.fp = 0;
+    }
+}
+
+/// AE (Asymmetric Extremum): cuts at local maxima that dominate a
+/// trailing window of `w = 1 << window_bits` bytes.
+///
+/// Unlike the rollsum-based chunkers above, AE needs no hash table
+/// lookups beyond the one `GEAR` indirection used here for a better byte
+/// distribution, and makes exactly one comparison per byte. That makes
+/// it considerably cheaper to run than `Bup`, `Gear` or `FastCdc`, at
+/// the cost of somewhat less even chunk sizes.
+pub struct Ae {
+    window: usize,
+    max_value: u64,
+    max_pos: usize,
+    pos: usize,
+}
+
+impl Ae {
+    pub fn new(window_bits: u32) -> Self {
+        Ae {
+            window: 1usize << window_bits,
+            max_value: 0,
+            max_pos: 0,
+            pos: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.max_value = 0;
+        self.max_pos = 0;
+        self.pos = 0;
+    }
+}
+
+impl Chunking for FastCdc {
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<usize> {
+        for (i, &b) in buf.iter().enumerate() {
+            if self.pos < self.min_size {
+                self.pos += 1;
+                continue;
+            }
+
+            self.fp = (self.fp << 1).wrapping_add(GEAR[b as usize]);
+
+            let mask = if self.pos < self.normal_size {
+                self.mask_s
+            } else {
+                self.mask_l
+            };
+
+            self.pos += 1;
+
+            if self.fp & mask == 0 || self.pos >= self.max_size {
+                self.reset();
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+impl Chunking for Ae {
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<usize> {
+        for (i, &b) in buf.iter().enumerate() {
+            let value = GEAR[b as usize];
+
+            if value <= self.max_value {
+                if self.pos - self.max_pos == self.window {
+                    self.reset();
+                    return Some(i + 1);
+                }
+            } else {
+                self.max_value = value;
+                self.max_pos = self.pos;
+            }
+
+            self.pos += 1;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random-ish bytes, so tests don't depend on
+    /// any particular real-world input.
+    pub fn input_of(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| ((i as u64).wrapping_mul(2654435761) >> 24) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn fastcdc_cuts_are_deterministic_and_within_bounds() {
+        let chunk_bits = 14;
+        let normal_size = 1usize << chunk_bits;
+        let min_size = normal_size / 4;
+        let max_size = normal_size * 8;
+
+        let data = input_of(max_size * 4);
+
+        let mut a = FastCdc::new(chunk_bits);
+        let mut b = FastCdc::new(chunk_bits);
+
+        let cut_a = a.find_chunk_edge(&data);
+        let cut_b = b.find_chunk_edge(&data);
+
+        assert_eq!(cut_a, cut_b, "FastCdc must be deterministic");
+        let cut = cut_a.expect("a long enough input must produce a cut");
+        assert!(cut >= min_size, "cut {} before min_size {}", cut, min_size);
+        assert!(cut <= max_size, "cut {} after max_size {}", cut, max_size);
+    }
+
+    #[test]
+    fn fastcdc_forces_a_cut_at_max_size() {
+        // On all-zero input the Gear hash never has its low bits all
+        // zero under either mask, so no edge is found before max_size
+        // and the forced cut must land exactly there.
+        let chunk_bits = 10;
+        let max_size = (1usize << chunk_bits) * 8;
+        let data = vec![0u8; max_size * 2];
+
+        let mut c = FastCdc::new(chunk_bits);
+        let cut = c.find_chunk_edge(&data).expect("must force a cut");
+        assert_eq!(cut, max_size);
+    }
+}
+
+#[cfg(test)]
+mod ae_tests {
+    use super::*;
+    use super::tests::input_of;
+
+    #[test]
+    fn ae_cuts_are_deterministic() {
+        let data = input_of(1 << 16);
+
+        let mut a = Ae::new(12);
+        let mut b = Ae::new(12);
+
+        assert_eq!(a.find_chunk_edge(&data), b.find_chunk_edge(&data));
+    }
+
+    #[test]
+    fn ae_resets_state_after_a_cut() {
+        let data = input_of(1 << 14);
+        let mut c = Ae::new(8);
+
+        if let Some(first_cut) = c.find_chunk_edge(&data) {
+            // After a cut, feeding the remainder must be independent of
+            // what came before: the chunker that just cut must find the
+            // same next edge, if any, as a fresh chunker fed only the
+            // remainder.
+            let mut fresh = Ae::new(8);
+            let remainder = &data[first_cut..];
+            assert_eq!(
+                fresh.find_chunk_edge(remainder),
+                c.find_chunk_edge(&data[first_cut..])
+            );
+        }
+    }
+}
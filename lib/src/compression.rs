@@ -0,0 +1,127 @@
+//! Compression: wraps the codecs `rdedup` can store chunks with
+//!
+//! Every variant boxed up as `ArcCompression` implements `Compression`,
+//! which is just enough to compress a chunk before writing it and
+//! decompress it again on read.
+
+use bzip2;
+use bzip2::read::BzDecoder;
+use bzip2::write::BzEncoder;
+
+use flate2;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+
+use zstd;
+
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+pub type ArcCompression = Arc<Compression>;
+
+pub trait Compression: Send + Sync {
+    fn compress(&self, buf: &[u8]) -> io::Result<Vec<u8>>;
+    fn decompress(&self, buf: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+pub struct NoCompression;
+
+impl Compression for NoCompression {
+    fn compress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(buf.to_vec())
+    }
+
+    fn decompress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(buf.to_vec())
+    }
+}
+
+/// DEFLATE, via `flate2`. `level` follows `flate2::Compression`'s 0-9
+/// scale; `None` keeps the previous default.
+pub struct Deflate {
+    pub level: Option<i32>,
+}
+
+impl Compression for Deflate {
+    fn compress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        let level = self.level
+            .map(|l| flate2::Compression::new(l as u32))
+            .unwrap_or_default();
+        let mut encoder = DeflateEncoder::new(Vec::new(), level);
+        encoder.write_all(buf)?;
+        encoder.finish()
+    }
+
+    fn decompress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoder = DeflateDecoder::new(buf);
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// xz/lzma2, via `xz2`. `level` is xz2's 0-9 preset; `None` keeps the
+/// previous default of 6.
+pub struct Xz2 {
+    pub level: Option<i32>,
+}
+
+impl Compression for Xz2 {
+    fn compress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        let level = self.level.unwrap_or(6) as u32;
+        let mut encoder = XzEncoder::new(Vec::new(), level);
+        encoder.write_all(buf)?;
+        encoder.finish()
+    }
+
+    fn decompress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoder = XzDecoder::new(buf);
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// bzip2, via `bzip2`. `level` is bzip2's 1-9 block-size factor; `None`
+/// keeps the previous default ("best" compression).
+pub struct Bzip2 {
+    pub level: Option<i32>,
+}
+
+impl Compression for Bzip2 {
+    fn compress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        let level = self.level
+            .map(|l| bzip2::Compression::new(l as u32))
+            .unwrap_or(bzip2::Compression::Best);
+        let mut encoder = BzEncoder::new(Vec::new(), level);
+        encoder.write_all(buf)?;
+        encoder.finish()
+    }
+
+    fn decompress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoder = BzDecoder::new(buf);
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// Zstandard, via `zstd`. `level` is zstd's 1-22 level; `None` keeps the
+/// previous default (zstd's own default level).
+pub struct Zstd {
+    pub level: Option<i32>,
+}
+
+impl Compression for Zstd {
+    fn compress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::stream::encode_all(buf, self.level.unwrap_or(0))
+    }
+
+    fn decompress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::stream::decode_all(buf)
+    }
+}
@@ -10,6 +10,7 @@ use compression;
 use encryption;
 use encryption::{ArcDecrypter, ArcEncrypter};
 use hashing;
+use index_format;
 
 use hex::ToHex;
 use settings;
@@ -20,7 +21,11 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 pub const REPO_VERSION_LOWEST: u32 = 0;
-pub const REPO_VERSION_CURRENT: u32 = 1;
+/// Bumped to 3: name/index entries now use `index_format::IndexFormat::V2`,
+/// a varint- and delta-encoded chunk reference list, in place of the
+/// fixed-width `V1` layout (version 2 added the per-chunk `ChunkHeader`
+/// for compression/hashing algorithm agility).
+pub const REPO_VERSION_CURRENT: u32 = 3;
 
 pub const DATA_SUBDIR: &'static str = "chunk";
 pub const NAME_SUBDIR: &'static str = "name";
@@ -64,6 +69,17 @@ pub enum Chunking {
     Bup { chunk_bits: u32 },
     #[serde(rename = "gear")]
     Gear { chunk_bits: u32 },
+    /// `FastCdc` uses a Gear rolling hash with *normalized chunking*,
+    /// giving much tighter chunk-size variance than `Bup`/`Gear` at
+    /// comparable speed. The valid range is the same as `Bup`/`Gear`.
+    #[serde(rename = "fastcdc")]
+    FastCdc { chunk_bits: u32 },
+    /// `Ae` (Asymmetric Extremum) needs no hash table and makes one
+    /// comparison per byte, trading some chunk-size evenness for speed.
+    /// `window_bits` sets the window size `w = 1 << window_bits`; the
+    /// valid range is the same as `Bup`/`Gear`/`FastCdc`.
+    #[serde(rename = "ae")]
+    Ae { window_bits: u32 },
 }
 
 /// Default implementation for the `Chunking`
@@ -80,6 +96,8 @@ impl Chunking {
         match self {
             Chunking::Bup { chunk_bits: bits } => 30 >= bits && bits >= 10,
             Chunking::Gear { chunk_bits: bits } => 30 >= bits && bits >= 10,
+            Chunking::FastCdc { chunk_bits: bits } => 30 >= bits && bits >= 10,
+            Chunking::Ae { window_bits: bits } => 30 >= bits && bits >= 10,
         }
     }
 
@@ -91,6 +109,12 @@ impl Chunking {
             Chunking::Gear { chunk_bits } => Box::new(
                 chunking::Gear::new(chunk_bits),
             ),
+            Chunking::FastCdc { chunk_bits } => Box::new(
+                chunking::FastCdc::new(chunk_bits),
+            ),
+            Chunking::Ae { window_bits } => Box::new(
+                chunking::Ae::new(window_bits),
+            ),
         }
     }
 }
@@ -99,31 +123,71 @@ impl Chunking {
 #[serde(tag = "type")]
 pub enum Compression {
     #[serde(rename = "deflate")]
-    Deflate,
+    Deflate {
+        #[serde(default)]
+        level: Option<i32>,
+    },
     #[serde(rename = "xz2")]
-    Xz2,
+    Xz2 {
+        #[serde(default)]
+        level: Option<i32>,
+    },
     #[serde(rename = "bzip2")]
-    Bzip2,
+    Bzip2 {
+        #[serde(default)]
+        level: Option<i32>,
+    },
     #[serde(rename = "zstd")]
-    Zstd,
+    Zstd {
+        #[serde(default)]
+        level: Option<i32>,
+    },
     #[serde(rename = "none")]
     None,
 }
 
 impl Default for Compression {
     fn default() -> Compression {
-        Compression::Deflate
+        Compression::Deflate { level: None }
     }
 }
 
 impl Compression {
+    /// Whether `level`, if set, falls within the range the underlying
+    /// codec accepts. `None` (the implicit default) is always valid.
+    pub fn valid(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Deflate { level } => {
+                level.map_or(true, |l| 0 <= l && l <= 9)
+            }
+            Compression::Xz2 { level } => {
+                level.map_or(true, |l| 0 <= l && l <= 9)
+            }
+            Compression::Bzip2 { level } => {
+                level.map_or(true, |l| 1 <= l && l <= 9)
+            }
+            Compression::Zstd { level } => {
+                level.map_or(true, |l| 1 <= l && l <= 22)
+            }
+        }
+    }
+
     pub(crate) fn to_engine(&self) -> compression::ArcCompression {
         match *self {
             Compression::None => Arc::new(compression::NoCompression),
-            Compression::Deflate => Arc::new(compression::Deflate),
-            Compression::Xz2 => Arc::new(compression::Xz2),
-            Compression::Bzip2 => Arc::new(compression::Bzip2),
-            Compression::Zstd => Arc::new(compression::Zstd),
+            Compression::Deflate { level } => {
+                Arc::new(compression::Deflate { level })
+            }
+            Compression::Xz2 { level } => {
+                Arc::new(compression::Xz2 { level })
+            }
+            Compression::Bzip2 { level } => {
+                Arc::new(compression::Bzip2 { level })
+            }
+            Compression::Zstd { level } => {
+                Arc::new(compression::Zstd { level })
+            }
         }
     }
 }
@@ -150,6 +214,15 @@ impl Hashing {
             Hashing::Blake2b => Arc::new(hashing::Blake2b),
         }
     }
+
+    /// Digest length in bytes, needed to know how much of a fixed-width
+    /// index entry is the digest versus the offset/length that follow it.
+    pub fn digest_len(&self) -> usize {
+        match *self {
+            Hashing::Sha256 => 32,
+            Hashing::Blake2b => 64,
+        }
+    }
 }
 
 /// Types of supported encryption
@@ -278,4 +351,156 @@ impl Repo {
 
         Ok(())
     }
+
+    /// Engine to use for reading/writing a chunk described by `header`,
+    /// rather than always using `self.compression` — this is what lets a
+    /// chunk written under a previous `compression` setting stay
+    /// readable after the repo's default changes.
+    pub(crate) fn compression_engine_for(
+        &self,
+        header: &ChunkHeader,
+    ) -> compression::ArcCompression {
+        header.compression.to_engine()
+    }
+
+    /// Hasher to use for a chunk described by `header`; see
+    /// `compression_engine_for`.
+    pub(crate) fn hasher_for(&self, header: &ChunkHeader) -> hashing::ArcHasher {
+        header.hashing.to_hasher()
+    }
+
+    /// Which `index_format::IndexFormat` this repo's name/index entries
+    /// are laid out in, based on the version it was written with.
+    pub(crate) fn index_format(&self) -> index_format::IndexFormat {
+        index_format::IndexFormat::for_repo_version(self.version)
+    }
+}
+
+/// Small self-describing header stored at the start of every chunk blob,
+/// recording the exact algorithms that were used to produce it.
+///
+/// On disk a chunk blob is `[4-byte big-endian header length][yaml
+/// header][compressed, hashed payload]`. New chunks are always written
+/// with `ChunkHeader::current`, using the repo's present-day settings.
+/// Because the header travels with the chunk, a reader never needs to
+/// guess: `ChunkHeader::split` pulls it off the front of the raw blob
+/// bytes, and the caller dispatches `to_engine()`/`to_hasher()` off that
+/// header instead of off the repo-wide `compression`/`hashing` config,
+/// so chunks written under older settings remain readable while new
+/// writes pick up whatever the repo has since moved to. That in turn is
+/// what makes a gradual `recompress`/`rekey` migration possible:
+/// existing chunks can be rewritten lazily, at the caller's convenience,
+/// rather than all at once.
+///
+/// Encryption is deliberately left out of this header for now: unlike
+/// compression/hashing, `Encryption::Curve25519` carries key material
+/// derived from a passphrase, and letting a chunk name an arbitrary past
+/// encryption scheme means the repo has to keep more than one keyed
+/// scheme around at once. That's a bigger change to `Repo` than this
+/// header alone, and is left for the `rekey` migration to introduce.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ChunkHeader {
+    pub version: u32,
+    pub compression: Compression,
+    pub hashing: Hashing,
+}
+
+impl ChunkHeader {
+    pub fn current(repo: &Repo) -> Self {
+        ChunkHeader {
+            version: REPO_VERSION_CURRENT,
+            compression: repo.compression,
+            hashing: repo.hashing,
+        }
+    }
+
+    /// Frame this header in front of `payload`, ready to be written out
+    /// as a complete chunk blob.
+    pub fn to_bytes(&self, payload: &[u8]) -> Vec<u8> {
+        let body =
+            serde_yaml::to_string(self).expect("yaml serialization failed");
+        let body = body.into_bytes();
+
+        let len = body.len() as u32;
+        let mut out = Vec::with_capacity(4 + body.len() + payload.len());
+        out.extend_from_slice(&[
+            (len >> 24) as u8,
+            (len >> 16) as u8,
+            (len >> 8) as u8,
+            len as u8,
+        ]);
+        out.extend_from_slice(&body);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// Split a raw chunk blob into its `ChunkHeader` and the remaining
+    /// payload bytes (the compressed, hashed chunk data).
+    pub fn split(blob: &[u8]) -> super::Result<(Self, &[u8])> {
+        if blob.len() < 4 {
+            return Err(
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "chunk blob shorter than header length prefix",
+                ).into(),
+            );
+        }
+
+        let header_len = ((blob[0] as u32) << 24 | (blob[1] as u32) << 16 |
+                              (blob[2] as u32) << 8 |
+                              (blob[3] as u32)) as usize;
+
+        let header_start = 4;
+        let header_end = header_start + header_len;
+        if blob.len() < header_end {
+            return Err(
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "chunk blob shorter than its declared header length",
+                ).into(),
+            );
+        }
+
+        let header_str = ::std::str::from_utf8(
+            &blob[header_start..header_end],
+        ).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "header is not utf8")
+        })?;
+
+        let header: ChunkHeader = serde_yaml::from_str(header_str)
+            .map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+            })?;
+
+        Ok((header, &blob[header_end..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_header_round_trips_through_bytes() {
+        let header = ChunkHeader {
+            version: REPO_VERSION_CURRENT,
+            compression: Compression::Zstd { level: Some(19) },
+            hashing: Hashing::Blake2b,
+        };
+        let payload = b"not actually compressed, just a test payload";
+
+        let blob = header.to_bytes(payload);
+        let (parsed, parsed_payload) = ChunkHeader::split(&blob).unwrap();
+
+        assert_eq!(parsed, header);
+        assert_eq!(parsed_payload, &payload[..]);
+    }
+
+    #[test]
+    fn compression_valid_rejects_out_of_range_levels() {
+        assert!(Compression::Zstd { level: Some(19) }.valid());
+        assert!(!Compression::Zstd { level: Some(23) }.valid());
+        assert!(!Compression::Deflate { level: Some(-1) }.valid());
+        assert!(Compression::Deflate { level: None }.valid());
+    }
 }
@@ -0,0 +1,339 @@
+//! Stats: repository usage and deduplication statistics
+//!
+//! `Stats::gather` walks a repo's `chunk`/`name`/`index` subdirectories
+//! (see `config::DATA_SUBDIR`/`NAME_SUBDIR`/`INDEX_SUBDIR`) and produces
+//! a `Stats` value that can be serialized the same way `config.yml` is,
+//! so a `stats` command can emit structured YAML rather than printing
+//! ad-hoc text.
+
+use config;
+use config::ChunkHeader;
+use index_format::{ChunkRef, IndexFormat};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Bytes and chunk count stored under one compression codec.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CompressionStats {
+    pub codec: String,
+    pub stored_bytes: u64,
+    pub chunk_count: u64,
+}
+
+/// A snapshot of a repository's size and deduplication effectiveness.
+///
+/// `logical_bytes` is how much data would be stored without
+/// deduplication (the sum of every chunk reference across every name,
+/// duplicates included); `physical_bytes` is what's actually on disk in
+/// `DATA_SUBDIR`. The gap between the two, `dedup_ratio`, is the payoff
+/// of content-defined chunking.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub chunking: String,
+    pub compression: String,
+    pub hashing: String,
+
+    pub chunk_count: u64,
+    pub duplicated_chunk_count: u64,
+
+    pub logical_bytes: u64,
+    pub physical_bytes: u64,
+
+    pub avg_chunk_size: f64,
+    pub chunk_size_stddev: f64,
+
+    pub by_compression: Vec<CompressionStats>,
+}
+
+impl Stats {
+    /// Fraction of logical bytes saved, `1 - physical / logical` (0 when
+    /// there are no logical bytes). At most 1; negative when
+    /// `physical_bytes` exceeds `logical_bytes`.
+    pub fn dedup_ratio(&self) -> f64 {
+        if self.logical_bytes == 0 {
+            0.0
+        } else {
+            1.0 -
+                (self.physical_bytes as f64 / self.logical_bytes as f64)
+        }
+    }
+
+    /// Gather stats for the repo rooted at `repo_path`.
+    pub fn gather(
+        repo_path: &Path,
+        repo: &config::Repo,
+    ) -> io::Result<Stats> {
+        let physical = gather_physical(repo_path)?;
+        let reference_counts = count_references(repo_path, repo)?;
+
+        let logical_bytes: u64 = reference_counts
+            .values()
+            .map(|r| r.total_len)
+            .sum();
+        let duplicated_chunk_count = reference_counts
+            .values()
+            .filter(|r| r.ref_count > 1)
+            .count() as u64;
+
+        Ok(Stats {
+            chunking: format!("{:?}", repo.chunking),
+            compression: format!("{:?}", repo.compression),
+            hashing: format!("{:?}", repo.hashing),
+            chunk_count: physical.chunk_count,
+            duplicated_chunk_count,
+            logical_bytes,
+            physical_bytes: physical.physical_bytes,
+            avg_chunk_size: physical.avg_chunk_size,
+            chunk_size_stddev: physical.chunk_size_stddev,
+            by_compression: physical.by_compression,
+        })
+    }
+}
+
+struct PhysicalStats {
+    chunk_count: u64,
+    physical_bytes: u64,
+    avg_chunk_size: f64,
+    chunk_size_stddev: f64,
+    by_compression: Vec<CompressionStats>,
+}
+
+/// Walk `DATA_SUBDIR`, reading each chunk blob's `ChunkHeader` (rather
+/// than assuming the repo's current `compression` setting) so a repo
+/// that has changed `compression` over time reports an accurate
+/// per-codec breakdown instead of attributing every chunk to whatever
+/// codec happens to be configured today.
+fn gather_physical(repo_path: &Path) -> io::Result<PhysicalStats> {
+    let data_dir = repo_path.join(config::DATA_SUBDIR);
+
+    let mut sizes = Vec::new();
+    let mut by_compression: HashMap<String, CompressionStats> =
+        HashMap::new();
+
+    walk_files(&data_dir, &mut |path| -> io::Result<()> {
+        let blob = fs::read(path)?;
+        sizes.push(blob.len() as u64);
+
+        let codec = match ChunkHeader::split(&blob) {
+            Ok((header, _payload)) => format!("{:?}", header.compression),
+            // Chunks predating chunk0-3's header framing (or any blob
+            // that fails to parse) are counted separately rather than
+            // silently folded into a codec they were never written
+            // with.
+            Err(_) => "unknown".to_string(),
+        };
+
+        let entry =
+            by_compression.entry(codec.clone()).or_insert_with(|| {
+                CompressionStats {
+                    codec,
+                    stored_bytes: 0,
+                    chunk_count: 0,
+                }
+            });
+        entry.stored_bytes += blob.len() as u64;
+        entry.chunk_count += 1;
+
+        Ok(())
+    })?;
+
+    let chunk_count = sizes.len() as u64;
+    let physical_bytes: u64 = sizes.iter().sum();
+
+    let avg_chunk_size = if chunk_count == 0 {
+        0.0
+    } else {
+        physical_bytes as f64 / chunk_count as f64
+    };
+
+    let chunk_size_stddev = if chunk_count == 0 {
+        0.0
+    } else {
+        let variance = sizes
+            .iter()
+            .map(|&s| {
+                let d = s as f64 - avg_chunk_size;
+                d * d
+            })
+            .sum::<f64>() / chunk_count as f64;
+        variance.sqrt()
+    };
+
+    Ok(PhysicalStats {
+        chunk_count,
+        physical_bytes,
+        avg_chunk_size,
+        chunk_size_stddev,
+        by_compression: by_compression.into_iter().map(|(_, v)| v).collect(),
+    })
+}
+
+/// Per-digest reference bookkeeping used to compute `logical_bytes` and
+/// `duplicated_chunk_count`.
+struct DigestRefs {
+    total_len: u64,
+    ref_count: u64,
+}
+
+/// Walk `NAME_SUBDIR`, follow each name to the index entry it resolves
+/// to, and tally the chunk references *that index lists* — a name and
+/// the index it points at are different layers (a name is just a
+/// pointer; the index is what actually lists real data-chunk refs), so
+/// only the index side is counted. Summing both would double-count
+/// every logical byte: once from the name's index, and again from
+/// that same index found directly under `INDEX_SUBDIR`.
+fn count_references(
+    repo_path: &Path,
+    repo: &config::Repo,
+) -> io::Result<HashMap<Vec<u8>, DigestRefs>> {
+    let format = repo.index_format();
+    let digest_len = repo.hashing.digest_len();
+    let index_dir = repo_path.join(config::INDEX_SUBDIR);
+    let name_dir = repo_path.join(config::NAME_SUBDIR);
+
+    let mut refs: HashMap<Vec<u8>, DigestRefs> = HashMap::new();
+
+    walk_files(&name_dir, &mut |path| -> io::Result<()> {
+        // A name file holds nothing but the digest of the index entry
+        // it resolves to.
+        let digest = fs::read(path)?;
+        if digest.len() != digest_len {
+            // Not a plain digest pointer (or corrupt); skip it rather
+            // than guess at a bogus index location.
+            return Ok(());
+        }
+
+        let index_path = repo.nesting.get_path(&index_dir, &digest);
+        let bytes = match fs::read(&index_path) {
+            Ok(bytes) => bytes,
+            // The name points at an index that isn't there; nothing to
+            // tally for it.
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(())
+            }
+            Err(e) => return Err(e),
+        };
+
+        let count = match format {
+            IndexFormat::V1 => {
+                let record_len = digest_len + 16;
+                if record_len == 0 || bytes.len() % record_len != 0 {
+                    // Not a plain reference-list file (or corrupt);
+                    // skip it rather than guess at a bogus count.
+                    return Ok(());
+                }
+                Some(bytes.len() / record_len)
+            }
+            IndexFormat::V2 => None,
+        };
+
+        let mut cursor = &bytes[..];
+        if let Ok(entries) =
+            format.read_entries(&mut cursor, digest_len, count)
+        {
+            for e in entries {
+                let r = refs.entry(e.digest).or_insert_with(|| DigestRefs {
+                    total_len: 0,
+                    ref_count: 0,
+                });
+                r.total_len += e.len;
+                r.ref_count += 1;
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(refs)
+}
+
+/// Recursively visit every regular file under `dir` (chunks/names/index
+/// entries are nested a few directories deep by `config::Nesting`),
+/// calling `f` with each file's path.
+fn walk_files<F>(dir: &Path, f: &mut F) -> io::Result<()>
+where
+    F: FnMut(&Path) -> io::Result<()>,
+{
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+
+        if metadata.is_dir() {
+            walk_files(&path, f)?;
+        } else {
+            f(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn scratch_repo_dir(name: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir()
+            .join(format!("rdedup_stats_test_{}_{}", ::std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn test_repo() -> config::Repo {
+        config::Repo {
+            version: config::REPO_VERSION_CURRENT,
+            chunking: config::Chunking::default(),
+            encryption: config::Encryption::None,
+            compression: config::Compression::None,
+            nesting: config::Nesting(0),
+            hashing: config::Hashing::Sha256,
+        }
+    }
+
+    #[test]
+    fn count_references_follows_name_to_its_index_without_double_counting() {
+        let repo_path = scratch_repo_dir("name_to_index");
+        let repo = test_repo();
+        let format = repo.index_format();
+        let digest_len = repo.hashing.digest_len();
+
+        // The index entry is the thing that actually lists chunk refs.
+        let chunk_refs = vec![
+            ChunkRef { digest: vec![0xaa; digest_len], offset: 0, len: 100 },
+            ChunkRef { digest: vec![0xbb; digest_len], offset: 100, len: 50 },
+        ];
+        let index_digest = vec![0xff; digest_len];
+
+        let index_dir = repo_path.join(config::INDEX_SUBDIR);
+        let index_path = repo.nesting.get_path(&index_dir, &index_digest);
+        fs::create_dir_all(index_path.parent().unwrap()).unwrap();
+        let mut buf = Vec::new();
+        format.write_entries(&mut buf, &chunk_refs).unwrap();
+        fs::write(&index_path, &buf).unwrap();
+
+        // The name is nothing but a pointer at that index.
+        let name_dir = repo_path.join(config::NAME_SUBDIR);
+        fs::create_dir_all(&name_dir).unwrap();
+        fs::write(name_dir.join("mybackup"), &index_digest).unwrap();
+
+        let refs = count_references(&repo_path, &repo).unwrap();
+        let logical_bytes: u64 = refs.values().map(|r| r.total_len).sum();
+
+        // Walking the name's index once should count each chunk once,
+        // not once for the name and again for the index it points at.
+        assert_eq!(logical_bytes, 150);
+        assert_eq!(refs.len(), 2);
+
+        fs::remove_dir_all(&repo_path).unwrap();
+    }
+}